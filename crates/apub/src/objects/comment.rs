@@ -4,6 +4,8 @@ use activitystreams::{object::kind::NoteType, public};
 use anyhow::anyhow;
 use chrono::NaiveDateTime;
 use html2md::parse_html;
+use once_cell::sync::Lazy;
+use regex::Regex;
 use url::Url;
 
 use lemmy_api_common::blocking;
@@ -16,6 +18,7 @@ use lemmy_db_schema::{
     comment::{Comment, CommentForm},
     community::Community,
     person::Person,
+    person_mention::{PersonMention, PersonMentionForm},
     post::Post,
   },
   traits::Crud,
@@ -29,9 +32,10 @@ use lemmy_websocket::LemmyContext;
 use crate::{
   activities::verify_person_in_community,
   fetcher::object_id::ObjectId,
+  objects::person::ApubPerson,
   protocol::{
     objects::{
-      note::{Note, SourceCompat},
+      note::{MaybeMention, Mention, MentionType, Note, SourceCompat},
       tombstone::Tombstone,
     },
     Source,
@@ -108,12 +112,18 @@ impl ApubObject for ApubComment {
       attributed_to: ObjectId::new(creator.actor_id),
       to: vec![public()],
       content: markdown_to_html(&self.content),
+      content_map: self.language.as_ref().map(|language| {
+        let mut map = std::collections::HashMap::new();
+        map.insert(language.clone(), markdown_to_html(&self.content));
+        map
+      }),
       media_type: Some(MediaTypeHtml::Html),
-      source: SourceCompat::Lemmy(Source {
+      source: SourceCompat::Markdown(Source {
         content: self.content.clone(),
         media_type: MediaTypeMarkdown::Markdown,
       }),
       in_reply_to,
+      tag: mention_tags(&self.content, context).await?,
       published: Some(convert_datetime(self.published)),
       updated: self.updated.map(convert_datetime),
       unparsed: Default::default(),
@@ -160,18 +170,41 @@ impl ApubObject for ApubComment {
       return Err(anyhow!("Post is locked").into());
     }
 
-    let content = if let SourceCompat::Lemmy(source) = &note.source {
+    let content = if let SourceCompat::Markdown(source) = &note.source {
+      // `source` is raw Markdown, not rendered HTML - `sanitize_html` is an HTML cleaner
+      // that parses and re-serializes its input, so running it here would HTML-escape
+      // ordinary characters (`<`, `>`, `&`) in legitimate comment text. `source` is
+      // matched by shape alone, not by verified instance software, so a hostile sender
+      // can claim this variant just as easily as a real Lemmy instance - but any raw HTML
+      // it smuggles in is neutralized where it's actually turned into HTML and rendered,
+      // by the same `markdown_to_html` sanitization `to_apub` already relies on for the
+      // `content` field, not by mangling the stored Markdown here.
       source.content.clone()
+    } else if !note.content.is_empty() {
+      parse_html(&sanitize_html(&note.content))
+    } else if let Some((_, content)) = note.content_map_entry() {
+      // No bare `content`, only a `contentMap` (e.g. some Mastodon/Misskey posts) -
+      // fall back to whichever language entry was selected.
+      parse_html(&sanitize_html(content))
     } else {
-      parse_html(&note.content)
+      String::new()
     };
     let content_slurs_removed = remove_slurs(&content, &context.settings().slur_regex());
+    // Read independent of which branch above produced `content`: Lemmy (and any instance
+    // matching its shape) sends `contentMap` alongside `source`/`content` with the same
+    // text whenever the comment has a language, so this is what round-trips the sender's
+    // own BCP-47 tag - requiring
+    // `content` to have literally come from this entry broke the main Lemmy-to-Lemmy
+    // case, since the `SourceCompat::Markdown` branch above wins first for every
+    // Lemmy-shaped note.
+    let language = note.content_map_entry().map(|(lang, _)| lang.to_string());
 
     let form = CommentForm {
       creator_id: creator.id,
       post_id: post.id,
       parent_id: parent_comment_id,
       content: content_slurs_removed,
+      language,
       removed: None,
       read: None,
       published: note.published.map(|u| u.to_owned().naive_local()),
@@ -180,8 +213,128 @@ impl ApubObject for ApubComment {
       ap_id,
       local: Some(false),
     };
-    let comment = blocking(context.pool(), move |conn| Comment::upsert(conn, &form)).await??;
-    Ok(comment.into())
+    let comment: ApubComment = blocking(context.pool(), move |conn| Comment::upsert(conn, &form))
+      .await??
+      .into();
+
+    save_mentions(&comment, &note.tag, context, request_counter).await;
+
+    Ok(comment)
+  }
+}
+
+/// Cleans remote `content` against ammonia's default tag/attribute allowlist, with
+/// `<iframe>`, `<style>` and `<script>` removed and URLs limited to http, https and mailto,
+/// before it is handed to [`parse_html`]. `html2md` itself is a converter, not a sanitizer,
+/// so without this a hostile Pleroma/Mastodon/Misskey note could carry `on*` handlers,
+/// `javascript:`/`data:` URLs, `<iframe>`/`<style>` injection, etc. straight into our
+/// stored markdown. `pub(crate)` so other objects that convert federated HTML can share it.
+pub(crate) fn sanitize_html(html: &str) -> String {
+  ammonia::Builder::default()
+    .rm_tags(&["iframe", "style", "script"])
+    .url_schemes(hashset(&["http", "https", "mailto"]))
+    .clean(html)
+    .to_string()
+}
+
+fn hashset(items: &[&str]) -> std::collections::HashSet<String> {
+  items.iter().map(|s| s.to_string()).collect()
+}
+
+static MENTION_REGEX: Lazy<Regex> =
+  Lazy::new(|| Regex::new(r"@(?P<name>[\w.]+)@(?P<domain>[\w.:-]+)").expect("compile regex"));
+
+/// A single federated `Note` mentions (and dereferences) at most this many accounts -
+/// `tag` is attacker-controlled and unbounded, and each entry can trigger a network
+/// fetch, so a pathological note can't force unbounded outbound requests either way.
+const MAX_MENTIONS_PER_COMMENT: usize = 20;
+
+/// The first [`MAX_MENTIONS_PER_COMMENT`] distinct `@name@domain` mentions written in
+/// `content`, in order of first appearance, deduplicated so the same account isn't looked
+/// up twice.
+fn extract_mentions(content: &str) -> Vec<(String, String)> {
+  let mut seen = std::collections::HashSet::new();
+  MENTION_REGEX
+    .captures_iter(content)
+    .filter_map(|captures| {
+      let name = captures.name("name")?.as_str().to_string();
+      let domain = captures.name("domain")?.as_str().to_string();
+      seen.insert((name.clone(), domain.clone())).then(|| (name, domain))
+    })
+    .take(MAX_MENTIONS_PER_COMMENT)
+    .collect()
+}
+
+/// Builds `Mention` tags for each mention [`extract_mentions`] finds in `content` that resolves to
+/// an actor already known locally, using its real `actor_id` - guessing a URL shape only
+/// happens to resolve for Lemmy peers, not Mastodon/Pleroma/Misskey.
+async fn mention_tags(
+  content: &str,
+  context: &LemmyContext,
+) -> Result<Vec<MaybeMention>, LemmyError> {
+  let mut tags = Vec::new();
+  for (name, domain) in extract_mentions(content) {
+    let lookup_name = name.clone();
+    let lookup_domain = domain.clone();
+    let person = blocking(context.pool(), move |conn| {
+      Person::find_by_name_and_domain(conn, &lookup_name, &lookup_domain)
+    })
+    .await??;
+    if let Some(person) = person {
+      tags.push(MaybeMention::Mention(Mention {
+        href: person.actor_id.into_inner(),
+        name: Some(format!("@{}@{}", name, domain)),
+        kind: MentionType::Mention,
+      }));
+    }
+  }
+  Ok(tags)
+}
+
+/// Dereferences the `Mention` tags among the first [`MAX_MENTIONS_PER_COMMENT`] entries of
+/// an incoming `Note`'s `tag` array (ignoring any other tag kind mixed into the same array,
+/// e.g. `Hashtag`/`Emoji`, though those still count towards that limit) and, for any that
+/// resolve to a local user, inserts a `PersonMention` row so that user gets notified of the
+/// reply.
+///
+/// The comment itself is already committed by the time this runs, so a failure here must
+/// not fail `from_apub` - that would both drop an otherwise-valid federated comment and,
+/// on retry, re-attempt mentions that already succeeded against the recipient/comment
+/// unique constraint. Each mention is therefore best-effort and failures are skipped.
+async fn save_mentions(
+  comment: &ApubComment,
+  tags: &[MaybeMention],
+  context: &LemmyContext,
+  request_counter: &mut i32,
+) {
+  let mut notified_person_ids = std::collections::HashSet::new();
+  for tag in tags.iter().take(MAX_MENTIONS_PER_COMMENT) {
+    let mention = match tag {
+      MaybeMention::Mention(m) => m,
+      MaybeMention::Other(_) => continue,
+    };
+    let mentioned_person = ObjectId::<ApubPerson>::new(mention.href.clone())
+      .dereference(context, request_counter)
+      .await;
+    let mentioned_person = match mentioned_person {
+      Ok(p) if p.local => p,
+      _ => continue,
+    };
+    // The same person can be @-mentioned more than once in a single comment; only
+    // insert one `PersonMention` row per recipient or the unique constraint on
+    // (comment_id, recipient_id) fails.
+    if !notified_person_ids.insert(mentioned_person.id) {
+      continue;
+    }
+    let form = PersonMentionForm {
+      recipient_id: mentioned_person.id,
+      comment_id: comment.id,
+      read: None,
+    };
+    let _ = blocking(context.pool(), move |conn| {
+      PersonMention::create(conn, &form)
+    })
+    .await;
   }
 }
 
@@ -241,6 +394,31 @@ pub(crate) mod tests {
     cleanup(data, &context);
   }
 
+  #[actix_rt::test]
+  #[serial]
+  async fn test_parse_comment_with_content_map_language() {
+    let context = init_context();
+    let url = Url::parse("https://enterprise.lemmy.ml/comment/38744").unwrap();
+    let data = prepare_comment_test(&url, &context).await;
+
+    // Carries both `source` (the Lemmy-shaped markdown-preservation path) and a
+    // `contentMap` tagging the language - the main round-trip case this feature exists
+    // for. `language` must come from `contentMap` even though `source` wins for `content`.
+    let json = file_to_json_object("assets/lemmy/objects/note_with_content_map.json");
+    let mut request_counter = 0;
+    let comment = ApubComment::from_apub(&json, &context, &url, &mut request_counter)
+      .await
+      .unwrap();
+    assert_eq!(comment.language.as_deref(), Some("fr"));
+
+    let to_apub = comment.to_apub(&context).await.unwrap();
+    let content_map = to_apub.content_map.unwrap();
+    assert_eq!(content_map.get("fr"), Some(&to_apub.content));
+
+    Comment::delete(&*context.pool().get().unwrap(), comment.id).unwrap();
+    cleanup(data, &context);
+  }
+
   #[actix_rt::test]
   #[serial]
   async fn test_parse_pleroma_comment() {
@@ -270,10 +448,86 @@ pub(crate) mod tests {
     cleanup(data, &context);
   }
 
+  #[actix_rt::test]
+  #[serial]
+  async fn test_parse_comment_with_local_mention() {
+    let context = init_context();
+    let url = Url::parse("https://enterprise.lemmy.ml/comment/38742").unwrap();
+    let data = prepare_comment_test(&url, &context).await;
+
+    let json = file_to_json_object("assets/lemmy/objects/note_with_mention.json");
+    let mut request_counter = 0;
+    let comment = ApubComment::from_apub(&json, &context, &url, &mut request_counter)
+      .await
+      .unwrap();
+
+    let mention = blocking(&context.pool(), {
+      let recipient_id = data.0.id;
+      let comment_id = comment.id;
+      move |conn| PersonMention::read_for_comment_and_recipient(conn, comment_id, recipient_id)
+    })
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(mention.comment_id, comment.id);
+    assert_eq!(mention.recipient_id, data.0.id);
+
+    Comment::delete(&*context.pool().get().unwrap(), comment.id).unwrap();
+    cleanup(data, &context);
+  }
+
+  #[test]
+  fn test_extract_mentions_reads_local_mention_syntax() {
+    let mentions = extract_mentions("hey @bob@example.com, thanks for the reply!");
+    assert_eq!(
+      mentions,
+      vec![("bob".to_string(), "example.com".to_string())]
+    );
+  }
+
+  #[test]
+  fn test_extract_mentions_dedupes_repeated_mentions() {
+    let mentions = extract_mentions("hey @bob@example.com, you there @bob@example.com?");
+    assert_eq!(
+      mentions,
+      vec![("bob".to_string(), "example.com".to_string())]
+    );
+  }
+
   #[actix_rt::test]
   #[serial]
   async fn test_html_to_markdown_sanitize() {
-    let parsed = parse_html("<script></script><b>hello</b>");
+    let parsed = parse_html(&sanitize_html("<script></script><b>hello</b>"));
     assert_eq!(parsed, "**hello**");
   }
+
+  #[test]
+  fn test_sanitize_html_strips_unsafe_content() {
+    assert_eq!(
+      sanitize_html(r#"<img src=x onerror="alert(1)">"#),
+      "<img src=\"x\">"
+    );
+    assert_eq!(
+      sanitize_html(r#"<a href="javascript:alert(1)">click</a>"#),
+      "<a rel=\"noopener noreferrer\">click</a>"
+    );
+    assert_eq!(
+      sanitize_html("<iframe src=\"https://evil.example\"></iframe><b>hi</b>"),
+      "<b>hi</b>"
+    );
+    assert_eq!(
+      sanitize_html("<style>body{display:none}</style><i>hi</i>"),
+      "<i>hi</i>"
+    );
+  }
+
+  #[test]
+  fn test_source_compat_parses_generic_markdown_source() {
+    let json = serde_json::json!({
+      "content": "**hi**",
+      "mediaType": "text/markdown",
+    });
+    let source: SourceCompat = serde_json::from_value(json).unwrap();
+    assert!(matches!(source, SourceCompat::Markdown(_)));
+  }
 }
@@ -0,0 +1,14 @@
+use lemmy_apub_lib::values::MediaTypeMarkdown;
+use serde::{Deserialize, Serialize};
+
+pub mod objects;
+
+/// A reference to the original Markdown source of an object, alongside a rendered
+/// `content` field, as described by <https://schema.org/RdfaNamedEntity> /
+/// `source` + `mediaType` conventions used across the fediverse.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Source {
+  pub content: String,
+  pub media_type: MediaTypeMarkdown,
+}
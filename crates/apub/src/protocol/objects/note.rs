@@ -0,0 +1,137 @@
+use activitystreams::{object::kind::NoteType, unparsed::Unparsed};
+use chrono::{DateTime, FixedOffset};
+use lemmy_apub_lib::{values::MediaTypeHtml, verify::verify_domains_match};
+use lemmy_db_schema::newtypes::CommentId;
+use lemmy_utils::LemmyError;
+use lemmy_websocket::LemmyContext;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+  fetcher::object_id::ObjectId,
+  objects::{comment::ApubComment, person::ApubPerson, post::ApubPost},
+  protocol::Source,
+  PostOrComment,
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Note {
+  pub(crate) r#type: NoteType,
+  pub(crate) id: Url,
+  pub(crate) attributed_to: ObjectId<ApubPerson>,
+  #[serde(default)]
+  pub(crate) to: Vec<Url>,
+  #[serde(default)]
+  pub(crate) content: String,
+  /// Alternate renderings of `content` keyed by BCP-47 language tag, as used by
+  /// Mastodon/Pleroma/Misskey for language-tagged posts.
+  #[serde(default)]
+  pub(crate) content_map: Option<std::collections::HashMap<String, String>>,
+  pub(crate) media_type: Option<MediaTypeHtml>,
+  #[serde(default)]
+  pub(crate) source: SourceCompat,
+  pub(crate) in_reply_to: ObjectId<PostOrComment>,
+  /// `Mention` tags for users referenced in `content`, so replies federate notifications
+  /// to the accounts they @-mention. Other tag kinds (`Hashtag`, `Emoji`, ...) are
+  /// routinely mixed into the same array by Mastodon/Pleroma/Misskey, so each entry is
+  /// parsed permissively and non-`Mention` tags are ignored rather than failing the note.
+  #[serde(default)]
+  pub(crate) tag: Vec<MaybeMention>,
+  pub(crate) published: Option<DateTime<FixedOffset>>,
+  pub(crate) updated: Option<DateTime<FixedOffset>>,
+  #[serde(flatten)]
+  pub(crate) unparsed: Unparsed,
+}
+
+/// Lemmy always sends the original markdown alongside the HTML in `source`, keyed as
+/// `mediaType: text/markdown`. Other implementations that advertise a `source` in the
+/// same shape (Mastodon, Pleroma, Misskey/MFM) match this variant too, so their original
+/// markdown is stored verbatim instead of being lossily re-derived from rendered HTML.
+/// Anything else - no `source`, or one in an unrecognized shape - falls through to
+/// `Other` and `from_apub` converts `content` instead.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum SourceCompat {
+  Markdown(Source),
+  Other(Option<serde_json::Value>),
+}
+
+impl Default for SourceCompat {
+  fn default() -> Self {
+    SourceCompat::Other(None)
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Mention {
+  pub(crate) href: Url,
+  pub(crate) name: Option<String>,
+  #[serde(rename = "type")]
+  pub(crate) kind: MentionType,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum MentionType {
+  Mention,
+}
+
+/// A `tag` array entry that may or may not be a `Mention` - AS2 implementations mix
+/// `Hashtag`, `Emoji` and other kinds into the same array, and those must be skipped
+/// rather than rejecting the whole `Note`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum MaybeMention {
+  Mention(Mention),
+  Other(serde_json::Value),
+}
+
+impl Note {
+  pub(crate) fn id(&self, expected_domain: &Url) -> Result<&Url, LemmyError> {
+    verify_domains_match(&self.id, expected_domain)?;
+    Ok(&self.id)
+  }
+
+  /// Picks one `contentMap` entry as `(language, rendered HTML)`: its HTML is used when
+  /// the note carries no bare `content`, and its language tag is stored for every note.
+  /// Prefers `en` for parity with Lemmy's own default; without an `en` entry it returns
+  /// an arbitrary one, as the order of the map's entries is not defined.
+  pub(crate) fn content_map_entry(&self) -> Option<(&str, &str)> {
+    let content_map = self.content_map.as_ref()?;
+    content_map
+      .get_key_value("en")
+      .or_else(|| content_map.iter().next())
+      .map(|(k, v)| (k.as_str(), v.as_str()))
+  }
+
+  /// Resolves `in_reply_to` to the post the comment belongs to, and the parent comment id
+  /// if the note is a reply to another comment rather than directly to the post.
+  pub(crate) async fn get_parents(
+    &self,
+    context: &LemmyContext,
+    request_counter: &mut i32,
+  ) -> Result<(ApubPost, Option<CommentId>), LemmyError> {
+    match self
+      .in_reply_to
+      .dereference(context, request_counter)
+      .await?
+    {
+      PostOrComment::Post(p) => Ok((p, None)),
+      PostOrComment::Comment(c) => {
+        let post_id = c.post_id;
+        let post = ObjectId::<ApubPost>::new(
+          lemmy_api_common::blocking(context.pool(), move |conn| {
+            lemmy_db_schema::source::post::Post::read(conn, post_id)
+          })
+          .await??
+          .ap_id
+          .into_inner(),
+        )
+        .dereference(context, request_counter)
+        .await?;
+        Ok((post, Some(c.id)))
+      }
+    }
+  }
+}
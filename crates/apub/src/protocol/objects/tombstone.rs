@@ -0,0 +1,22 @@
+use activitystreams::object::kind::TombstoneType;
+use chrono::NaiveDateTime;
+use lemmy_utils::utils::convert_datetime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tombstone {
+  r#type: TombstoneType,
+  former_type: String,
+  deleted: String,
+}
+
+impl Tombstone {
+  pub fn new<T: ToString>(former_type: T, deleted: NaiveDateTime) -> Self {
+    Tombstone {
+      r#type: TombstoneType::Tombstone,
+      former_type: former_type.to_string(),
+      deleted: convert_datetime(deleted).to_rfc3339(),
+    }
+  }
+}